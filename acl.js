@@ -0,0 +1,39 @@
+import { BlockList, isIPv4, isIPv6 } from "net";
+
+// addresses and subnets as a BlockList, throwing on an invalid one
+export function cidrList(cidrs) {
+  const blockList = new BlockList();
+  for (const cidr of cidrs) {
+    const [address, prefix] = String(cidr).split("/");
+    const type = isIPv6(address) ? "ipv6" : "ipv4";
+    const bits = prefix ?? (type === "ipv6" ? 128 : 32);
+    if (!/^\d+$/.test(bits)) throw new Error(`invalid prefix /${bits}`);
+    blockList.addSubnet(address, Number(bits), type);
+  }
+  return blockList;
+}
+
+// a host's allow/deny cidr lists, built once per setting; loadConfig
+// has checked that cidrList() accepts them
+const acls = new WeakMap();
+
+function acl(setting) {
+  if (!acls.has(setting)) {
+    acls.set(setting, {
+      allow: setting.allow && cidrList(setting.allow),
+      deny: cidrList(setting.deny ?? []),
+    });
+  }
+  return acls.get(setting);
+}
+
+// whether a client address passes a host's allow/deny lists
+export function permitted(setting, remoteAddress) {
+  const mapped = remoteAddress.replace(/^::ffff:/, "");
+  const [address, type] = isIPv4(mapped)
+    ? [mapped, "ipv4"]
+    : [remoteAddress, "ipv6"];
+  const { allow, deny } = acl(setting);
+  if (deny.check(address, type)) return false;
+  return allow === undefined || allow.check(address, type);
+}
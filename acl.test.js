@@ -0,0 +1,42 @@
+import assert from "node:assert/strict";
+import { test } from "node:test";
+import { cidrList, permitted } from "./acl.js";
+
+test("allows only listed addresses when allow is set", () => {
+  const setting = { allow: ["127.0.0.1/32", "192.168.0.0/16"] };
+  assert.ok(permitted(setting, "127.0.0.1"));
+  assert.ok(permitted(setting, "192.168.3.4"));
+  assert.ok(!permitted(setting, "10.0.0.1"));
+});
+
+test("lets deny win over allow", () => {
+  const setting = { allow: ["192.168.0.0/16"], deny: ["192.168.1.13"] };
+  assert.ok(permitted(setting, "192.168.1.12"));
+  assert.ok(!permitted(setting, "192.168.1.13"));
+});
+
+test("allows everything not denied without an allow list", () => {
+  const setting = { deny: ["10.0.0.0/8"] };
+  assert.ok(permitted(setting, "172.16.0.1"));
+  assert.ok(!permitted(setting, "10.1.2.3"));
+});
+
+test("matches IPv4-mapped IPv6 addresses against IPv4 entries", () => {
+  const setting = { allow: ["127.0.0.1"], deny: ["192.168.1.13"] };
+  assert.ok(permitted(setting, "::ffff:127.0.0.1"));
+  assert.ok(!permitted(setting, "::ffff:192.168.1.13"));
+  assert.ok(!permitted(setting, "::ffff:10.0.0.1"));
+});
+
+test("matches IPv6 addresses against IPv6 entries", () => {
+  const setting = { allow: ["::1", "fd00::/8"] };
+  assert.ok(permitted(setting, "::1"));
+  assert.ok(permitted(setting, "fd12:3456::1"));
+  assert.ok(!permitted(setting, "2001:db8::1"));
+});
+
+test("throws on an invalid entry", () => {
+  assert.throws(() => cidrList(["10.0.0.0/x"]), /invalid prefix/);
+  assert.throws(() => cidrList(["10.0.0.0/33"]));
+  assert.throws(() => cidrList(["not-an-address"]));
+});
@@ -0,0 +1,11 @@
+// the local url a dev server announces, e.g. vite's
+// "Local: http://localhost:5174/" after it skipped a busy 5173; the host
+// is kept, a server on localhost may listen on ::1 only, and 0.0.0.0
+// (all addresses) is reached on 127.0.0.1
+export function announcedUrl(line) {
+  const plain = line.replace(/\x1b\[[0-9;]*m/g, "");
+  const url = /https?:\/\/(localhost|127\.0\.0\.1|0\.0\.0\.0|\[::1\]):(\d+)/;
+  const [, host, port] = plain.match(url) ?? [];
+  if (!port) return;
+  return `http://${host === "0.0.0.0" ? "127.0.0.1" : host}:${port}`;
+}
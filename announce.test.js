@@ -0,0 +1,40 @@
+import assert from "node:assert/strict";
+import { test } from "node:test";
+import { announcedUrl } from "./announce.js";
+
+test("finds the url in a dev server's output", () => {
+  assert.equal(
+    announcedUrl("  ➜  Local:   http://localhost:5174/"),
+    "http://localhost:5174",
+  );
+  assert.equal(
+    announcedUrl("Server running at http://127.0.0.1:3000/"),
+    "http://127.0.0.1:3000",
+  );
+});
+
+test("keeps an IPv6 loopback host", () => {
+  assert.equal(
+    announcedUrl("Listening on http://[::1]:8080"),
+    "http://[::1]:8080",
+  );
+});
+
+test("reaches 0.0.0.0 on 127.0.0.1", () => {
+  assert.equal(
+    announcedUrl("Serving on http://0.0.0.0:4000/"),
+    "http://127.0.0.1:4000",
+  );
+});
+
+test("ignores ANSI colours", () => {
+  const line =
+    "  \x1b[32m➜\x1b[39m  \x1b[1mLocal\x1b[22m:   " +
+    "\x1b[36mhttp://localhost:\x1b[1m5173\x1b[22m/\x1b[39m";
+  assert.equal(announcedUrl(line), "http://localhost:5173");
+});
+
+test("ignores lines without a local url", () => {
+  assert.equal(announcedUrl("compiled in 120ms"), undefined);
+  assert.equal(announcedUrl("Network: http://192.168.1.2:5173/"), undefined);
+});
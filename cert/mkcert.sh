@@ -5,3 +5,6 @@ cd cert
 mkcert alice.example
 mkcert bob.example
 mkcert charlie.example
+mkcert dave.example
+mkcert "*.myproj.example"
+mkcert erin.example
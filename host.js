@@ -0,0 +1,9 @@
+import { domainToASCII } from "url";
+
+// canonical host form, used for every host comparison: no port or
+// trailing dot, lowercase punycode as browsers send in SNI and Host;
+// "" for a missing host, e.g. an HTTP/1.0 request without Host
+export function normalizeHost(host = "") {
+  const name = host.replace(/:\d+$/, "").replace(/\.$/, "");
+  return domainToASCII(name) || name;
+}
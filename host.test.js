@@ -0,0 +1,29 @@
+import assert from "node:assert/strict";
+import { test } from "node:test";
+import { normalizeHost } from "./host.js";
+
+test("lowercases", () => {
+  assert.equal(normalizeHost("App.Example"), "app.example");
+});
+
+test("strips the port", () => {
+  assert.equal(normalizeHost("app.example:8443"), "app.example");
+});
+
+test("strips a trailing dot", () => {
+  assert.equal(normalizeHost("app.example."), "app.example");
+  assert.equal(normalizeHost("app.example.:443"), "app.example");
+});
+
+test("converts unicode labels to punycode", () => {
+  assert.equal(normalizeHost("Bücher.example"), "xn--bcher-kva.example");
+  assert.equal(
+    normalizeHost("xn--bcher-kva.example"),
+    "xn--bcher-kva.example",
+  );
+});
+
+test("returns an empty string for a missing host", () => {
+  assert.equal(normalizeHost(undefined), "");
+  assert.equal(normalizeHost(""), "");
+});
@@ -0,0 +1,50 @@
+// names and sizes of the parts of a multipart/form-data body as it
+// streams past, without keeping file contents around
+export function multipartParts(boundary) {
+  const delimiter = Buffer.from(`\r\n--${boundary}`);
+  const parts = [];
+  let state = "body"; // body => after => headers => body ... => done
+  let part; // the part whose body is being read, none in the preamble
+  // the body opens with --boundary, without the CRLF of later delimiters
+  let pending = Buffer.from("\r\n");
+
+  const write = (chunk) => {
+    let buf = Buffer.concat([pending, chunk]);
+    for (;;) {
+      if (state === "body") {
+        const index = buf.indexOf(delimiter);
+        if (index === -1) {
+          // keep enough to see a delimiter split across chunks
+          const keep = Math.min(buf.length, delimiter.length - 1);
+          if (part) part.size += buf.length - keep;
+          buf = buf.subarray(buf.length - keep);
+          break;
+        }
+        if (part) parts.push({ ...part, size: part.size + index });
+        part = undefined;
+        buf = buf.subarray(index + delimiter.length);
+        state = "after";
+      } else if (state === "after") {
+        if (buf.length < 2) break;
+        state = buf.subarray(0, 2).toString() === "--" ? "done" : "headers";
+        buf = buf.subarray(2);
+      } else if (state === "headers") {
+        const end = buf.indexOf("\r\n\r\n");
+        if (end === -1) break;
+        const head = buf.subarray(0, end).toString();
+        part = {
+          name: head.match(/\bname="([^"]*)"/i)?.[1],
+          filename: head.match(/\bfilename="([^"]*)"/i)?.[1],
+          size: 0,
+        };
+        buf = buf.subarray(end + 4);
+        state = "body";
+      } else {
+        buf = Buffer.alloc(0);
+        break;
+      }
+    }
+    pending = buf;
+  };
+  return { write, parts };
+}
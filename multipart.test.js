@@ -0,0 +1,61 @@
+import assert from "node:assert/strict";
+import { test } from "node:test";
+import { multipartParts } from "./multipart.js";
+
+const boundary = "----form1234";
+const body = Buffer.from(
+  [
+    "preamble",
+    `--${boundary}`,
+    'Content-Disposition: form-data; name="title"',
+    "",
+    "hello",
+    `--${boundary}`,
+    'Content-Disposition: form-data; name="file"; filename="a.txt"',
+    "Content-Type: text/plain",
+    "",
+    "line one\r\nline two",
+    `--${boundary}--`,
+    "epilogue",
+  ].join("\r\n"),
+);
+const expected = [
+  { name: "title", filename: undefined, size: 5 },
+  { name: "file", filename: "a.txt", size: 18 },
+];
+
+const parse = (chunks) => {
+  const { write, parts } = multipartParts(boundary);
+  for (const chunk of chunks) write(chunk);
+  return parts;
+};
+
+test("reads names, filenames and sizes", () => {
+  assert.deepEqual(parse([body]), expected);
+});
+
+test("handles a body split at every position", () => {
+  for (let i = 1; i < body.length; i++) {
+    const parts = parse([body.subarray(0, i), body.subarray(i)]);
+    assert.deepEqual(parts, expected, `split at ${i}`);
+  }
+});
+
+test("handles one byte at a time", () => {
+  const bytes = [...body].map((byte) => Buffer.from([byte]));
+  assert.deepEqual(parse(bytes), expected);
+});
+
+test("counts a delimiter-like line inside a part as its content", () => {
+  const data = `x\r\n--${boundary.slice(0, -1)}\r\ny`;
+  const { write, parts } = multipartParts(boundary);
+  write(
+    Buffer.from(
+      `--${boundary}\r\nContent-Disposition: form-data; name="f"\r\n\r\n` +
+        `${data}\r\n--${boundary}--\r\n`,
+    ),
+  );
+  assert.deepEqual(parts, [
+    { name: "f", filename: undefined, size: data.length },
+  ]);
+});
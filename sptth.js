@@ -1,15 +1,43 @@
 #!/usr/bin/env node
 
-import { readFile } from "fs/promises";
-import { resolve } from "path";
+import { execFileSync, spawn } from "child_process";
+import {
+  createHmac,
+  createPublicKey,
+  randomUUID,
+  timingSafeEqual,
+  verify,
+  X509Certificate,
+} from "crypto";
+import { lookup } from "dns/promises";
+import { existsSync, readFileSync, watch, writeFileSync } from "fs";
+import { access, readFile } from "fs/promises";
+import { createServer as createHttpServer, request, STATUS_CODES } from "http";
+import { get } from "https";
+import { connect, createServer } from "net";
+import { networkInterfaces, tmpdir, uptime } from "os";
+import { PassThrough } from "stream";
+import { basename, dirname, join, resolve } from "path";
 import redbird from "redbird";
-import { parse } from "yaml";
+import { connect as tlsConnect, rootCertificates } from "tls";
+import { format } from "util";
+import { LineCounter, parse, parseDocument } from "yaml";
+import { createBrotliDecompress, createGunzip, createInflate } from "zlib";
+import { cidrList, permitted } from "./acl.js";
+import { announcedUrl } from "./announce.js";
+import { normalizeHost } from "./host.js";
+import { multipartParts } from "./multipart.js";
+import { compareVersions } from "./version.js";
 
 function help() {
   console.log(
     `
 sptth is the HTTPS reverse proxy cli (https <-> sptth)
-$ sptth proxy.yaml
+$ sptth proxy.yaml [--open]
+$ sptth check proxy.yaml
+$ sptth test alice.example [port]
+$ sptth self-update [--check]
+$ sptth version [--json]
 
 \`\`\`yaml
 sptth:
@@ -18,37 +46,1786 @@ sptth:
       key: ./cert/alice.example-key.pem
       cert: ./cert/alice.example.pem
       url: http://127.0.0.1:3000
+      command: npm run dev # started, restarted on crash, stopped with sptth
+      cwd: ../alice
+      cookie_jar: true # curl -H "x-sptth-jar: me" keeps cookies in sptth
+    erin.example:
+      key: ./cert/erin.example-key.pem
+      cert: ./cert/erin.example.pem
+      url: auto # port the command prints, e.g. vite's Local: url
+      command: npx vite
+      grep: ["localhost:5173", "http://"] # report matches in responses
+      validate_caching: true # log Range/If-None-Match the upstream ignored
+      log_multipart: true # log form field names and file sizes of uploads
+      open: true
+      enabled: true # false keeps the entry but skips it
     bob.example:
       key: ./cert/bob.example-key.pem
       cert: ./cert/bob.example.pem
       url: http://127.0.0.1:4000
+      default: true # catch-all for unknown hosts
+      allow: [127.0.0.1/32, 192.168.0.0/16] # client addresses, deny wins
+      deny: [192.168.1.13]
+      allowed_methods: [GET, POST] # 405 otherwise
+      max_header_count: 50 # 431 above either limit
+      max_header_bytes: 8192
+      max_concurrent_requests: 4 # more wait in a queue, 503 once it is full
+      max_queued_requests: 16 # default 100
+      forward_auth: http://127.0.0.1:9091/verify # only 2xx get through
+      forward_auth_headers: [Remote-User] # copied from its answer upstream
+      jwt: # bearer token required, 401 otherwise
+        jwks_url: http://127.0.0.1:9091/jwks.json # or secret, or public_key
+        issuer: http://127.0.0.1:9091
+        audience: bob
+    charlie.example:
+      key: ./cert/charlie.example-key.pem
+      cert: ./cert/charlie.example.pem
+      url: docker://charlie:8080
+    dave.example:
+      key: ./cert/dave.example-key.pem
+      cert: ./cert/dave.example.pem
+      url: k8s://default/dave:80
+  # html page that reloads once the upstream is back, instead of a bare 502
+  outage_page: true
+  # list all hosts on requests for unknown hosts
+  index: true
+  # shell command, or url to POST the event as json
+  hooks:
+    on_start: echo "sptth is up"
+    on_upstream_down: http://127.0.0.1:9000/sptth
+    on_reload: echo "$SPTTH_EVENT"
+  # log proxied requests slower than this many milliseconds
+  slow_ms: 1000
+  # 421 when the Host header does not match the TLS server name (SNI)
+  sni_mismatch: true
+  # close clients that are slow to send headers or stay idle (slowloris);
+  # the tls handshake itself is limited to node's default 120s
+  timeouts:
+    headers_ms: 10000 # complete request headers
+    request_ms: 60000 # complete request, headers and body
+    keep_alive_ms: 5000 # between requests on a kept-alive connection
+    idle_ms: 120000 # no bytes either way
+  # connections beyond these are closed right after accept
+  max_connections: 512
+  max_connections_per_ip: 64
+  # desktop notifications when an upstream goes down or comes back
+  notifications: true
+  # https://<port>.localhost and https://*.<port>.localhost => 127.0.0.1:<port>
+  localhost_ports: true
+  # directories whose own sptth.yaml (hosts with http urls only) is
+  # applied live; hosts already routed elsewhere are skipped
+  projects:
+    - ../alice
+    - ../bob
+  # app/api/admin.myproj.example => 127.0.0.1:<port_map>
+  templates:
+    - base: myproj.example
+      domains: [app, api, admin]
+      port_map: { app: 3000, api: 8080, admin: 8081 }
+      key: ./cert/_wildcard.myproj.example-key.pem
+      cert: ./cert/_wildcard.myproj.example.pem
+  # <service>.myproj.example for each published docker-compose service
+  compose:
+    file: ./docker-compose.yml
+    base: myproj.example
+    key: ./cert/_wildcard.myproj.example-key.pem
+    cert: ./cert/_wildcard.myproj.example.pem
 \`\`\`
   `.trim(),
   );
   process.exit(0);
 }
 
+// exit codes from sysexits.h, so wrapper scripts can tell failures apart
+const EXIT = {
+  CONFIG: 78, // EX_CONFIG: config unreadable or invalid
+  UPSTREAM: 69, // EX_UNAVAILABLE: docker/k8s upstream, npm or its registry
+  SOFTWARE: 70, // EX_SOFTWARE: unexpected runtime fault
+  BIND: 71, // EX_OSERR: listen port in use or not permitted
+};
+
+function exit(code, message) {
+  console.error(message);
+  process.exit(code);
+}
+
+// Docker Engine API over the local socket
+const DOCKER_SOCKET = "/var/run/docker.sock";
+
+function docker(path) {
+  return new Promise((resolve, reject) => {
+    request({ socketPath: DOCKER_SOCKET, path }, (res) => {
+      let body = "";
+      res.setEncoding("utf-8");
+      res.on("data", (chunk) => (body += chunk));
+      res.on("end", () => {
+        if (res.statusCode !== 200) {
+          return reject(new Error(`docker ${path}: ${body.trim()}`));
+        }
+        resolve(JSON.parse(body));
+      });
+    })
+      .on("error", reject)
+      .end();
+  });
+}
+
+// stream container events one parsed line at a time, reconnecting
+// whenever docker closes the stream (e.g. the daemon restarted)
+function dockerEvents(filters, onEvent) {
+  const path = `/events?filters=${encodeURIComponent(JSON.stringify(filters))}`;
+  // a stream can both fail and close, it is reopened once
+  let reconnecting = false;
+  const reconnect = (reason) => {
+    if (reconnecting) return;
+    reconnecting = true;
+    console.error(`docker events ${reason}, reconnecting`);
+    setTimeout(() => dockerEvents(filters, onEvent), 1000);
+  };
+  request({ socketPath: DOCKER_SOCKET, path }, (res) => {
+    // chunks may end mid-line, keep the rest for the next one
+    let rest = "";
+    res.setEncoding("utf-8").on("data", (chunk) => {
+      const lines = (rest + chunk).split("\n");
+      rest = lines.pop();
+      for (const line of lines.filter(Boolean)) onEvent(JSON.parse(line));
+    });
+    res.on("error", (err) => reconnect(err.message));
+    res.on("close", () => reconnect("closed"));
+  })
+    .on("error", (err) => reconnect(err.message))
+    .end();
+}
+
+// docker://container:port
+const DOCKER_URL = /^docker:\/\/[\w.-]+:\d+$/;
+
+// docker://container:port => published port on loopback, or bridge ip
+async function resolveDocker(url) {
+  const { hostname, port } = new URL(url);
+  const { State, NetworkSettings } = await docker(
+    `/containers/${hostname}/json`,
+  );
+  if (!State.Running) throw new Error(`container ${hostname} is not running`);
+  const published = NetworkSettings.Ports?.[`${port}/tcp`]?.[0];
+  if (published) {
+    return `http://127.0.0.1:${published.HostPort}`;
+  }
+  const [network] = Object.values(NetworkSettings.Networks);
+  return `http://${network.IPAddress}:${port}`;
+}
+
+// pick a free loopback port for kubectl to listen on
+function freePort() {
+  return new Promise((resolve) => {
+    const server = createServer().listen(0, "127.0.0.1", () => {
+      const { port } = server.address();
+      server.close(() => resolve(port));
+    });
+  });
+}
+
+// k8s://namespace/service:port
+const K8S_URL = /^k8s:\/\/([^/]+)\/([^:]+):(\d+)$/;
+
+// kubectl port-forward on a local port, restarted whenever it exits
+// (e.g. the backing pod was replaced); its errors are tagged with the host
+async function resolveK8s(key, url) {
+  const [, namespace, service, port] = url.match(K8S_URL);
+  const local = await freePort();
+  const forward = () => {
+    const kubectl = spawn(
+      "kubectl",
+      [
+        "port-forward",
+        "-n",
+        namespace,
+        `service/${service}`,
+        `${local}:${port}`,
+      ],
+      {
+        stdio: ["ignore", "ignore", "pipe"],
+        detached: process.platform !== "win32",
+      },
+    );
+    children.add(kubectl);
+    saveChildren();
+    prefixLines(kubectl.stderr, process.stderr, key);
+    kubectl.on("error", (err) =>
+      exit(EXIT.UPSTREAM, `kubectl: ${err.message}`),
+    );
+    kubectl.on("exit", () => {
+      if (!children.delete(kubectl)) return; // stopped by us
+      saveChildren();
+      setTimeout(forward, 1000);
+    });
+  };
+  forward();
+  return `http://127.0.0.1:${local}`;
+}
+
+// <service>.<base> => first published host port of each compose service
+async function composeRoutes(compose) {
+  const file = await readFile(resolve(compose.file), { encoding: "utf-8" });
+  const { services } = parse(file);
+  const routes = {};
+  for (const [name, service] of Object.entries(services ?? {})) {
+    const [port] = service.ports ?? [];
+    const published =
+      typeof port === "object"
+        ? port.published
+        : String(port).split(":").at(-2);
+    if (published === undefined) continue;
+    const host = normalizeHost(`${name}.${compose.base}`);
+    routes[host] = `http://127.0.0.1:${published}`;
+  }
+  return routes;
+}
+
+async function readPackage() {
+  const file = new URL("./package.json", import.meta.url);
+  return JSON.parse(await readFile(file, { encoding: "utf-8" }));
+}
+
+// version of the sptth.yaml format, bumped on incompatible changes so
+// tooling can tell which options a given sptth understands
+const CONFIG_SCHEMA_VERSION = 1;
+
+// the git commit when run from a sptth checkout, null when installed from
+// npm (also inside someone else's repository)
+function gitCommit() {
+  try {
+    const dir = new URL(".", import.meta.url).pathname;
+    const args = ["-C", dir, "rev-parse", "--show-toplevel", "HEAD"];
+    const [toplevel, commit] = execFileSync("git", args, {
+      encoding: "utf-8",
+      stdio: ["ignore", "pipe", "ignore"],
+    }).split("\n");
+    return resolve(toplevel) === resolve(dir) ? commit : null;
+  } catch {
+    return null;
+  }
+}
+
+function version(pkg, json) {
+  if (json) {
+    const { name, version } = pkg;
+    const { node } = process.versions;
+    const schema = CONFIG_SCHEMA_VERSION;
+    const commit = gitCommit();
+    console.log(JSON.stringify({ name, version, node, schema, commit }));
+  } else {
+    console.log(`${pkg.name} ${pkg.version}`);
+  }
+}
+
+// update the globally installed package from the npm registry,
+// npm verifies the tarball integrity before replacing it
+async function selfUpdate(check) {
+  const pkg = await readPackage();
+  const registry = `https://registry.npmjs.org/${pkg.name}/latest`;
+  const res = await fetch(registry).catch((err) => err);
+  if (res instanceof Error) {
+    console.error(`${registry}: ${res.message}`);
+    return EXIT.UPSTREAM;
+  }
+  if (!res.ok) {
+    console.error(`${registry}: ${res.status} ${res.statusText}`);
+    return EXIT.UPSTREAM;
+  }
+  const { version } = await res.json();
+  if (compareVersions(version, pkg.version) <= 0) {
+    console.log(`sptth ${pkg.version} is up to date (latest ${version})`);
+    return 0;
+  }
+  console.log(`sptth ${pkg.version} => ${version}`);
+  if (check) return 1;
+  // npm is npm.cmd on windows, which only a shell runs
+  const npm = spawn("npm", ["install", "--global", `${pkg.name}@${version}`], {
+    stdio: "inherit",
+    shell: process.platform === "win32",
+  });
+  return new Promise((resolve) => {
+    npm.on("exit", resolve);
+    npm.on("error", (err) => {
+      console.error(`npm: ${err.message}`);
+      resolve(EXIT.UPSTREAM);
+    });
+  });
+}
+
+// the first problem with a host entry as [field, message], if any
+function hostError(setting) {
+  for (const field of ["key", "cert", "url"]) {
+    if (typeof setting?.[field] !== "string") return [field, "is required"];
+  }
+  if (setting.url.startsWith("k8s://") && !K8S_URL.test(setting.url)) {
+    return ["url", "must be k8s://namespace/service:port"];
+  }
+  if (setting.url.startsWith("docker://") && !DOCKER_URL.test(setting.url)) {
+    return ["url", "must be docker://container:port"];
+  }
+  if (setting.command !== undefined && typeof setting.command !== "string") {
+    return ["command", "must be a string"];
+  }
+  if (setting.cwd !== undefined && !existsSync(String(setting.cwd))) {
+    return ["cwd", `${setting.cwd} does not exist`];
+  }
+  for (const field of ["allow", "deny"]) {
+    if (setting[field] === undefined) continue;
+    if (!Array.isArray(setting[field])) return [field, "must be a list"];
+    for (const cidr of setting[field]) {
+      try {
+        cidrList([cidr]);
+      } catch (err) {
+        return [field, `has an invalid entry ${cidr}: ${err.message}`];
+      }
+    }
+  }
+  const { forward_auth } = setting;
+  if (forward_auth !== undefined && !/^https?:\/\//.test(forward_auth)) {
+    return ["forward_auth", "must be an http(s) url"];
+  }
+  const { allowed_methods } = setting;
+  if (allowed_methods !== undefined) {
+    if (!Array.isArray(allowed_methods)) {
+      return ["allowed_methods", "must be a list"];
+    }
+    if (!allowed_methods.every((method) => typeof method === "string")) {
+      return ["allowed_methods", "must list method names"];
+    }
+  }
+  for (const field of ["max_header_count", "max_header_bytes"]) {
+    const limit = setting[field];
+    if (limit !== undefined && !(Number.isInteger(limit) && limit > 0)) {
+      return [field, "must be a positive number"];
+    }
+  }
+  for (const field of ["max_concurrent_requests", "max_queued_requests"]) {
+    const limit = setting[field];
+    if (limit !== undefined && !(Number.isInteger(limit) && limit >= 0)) {
+      return [field, "must be a number"];
+    }
+  }
+  const { jwt } = setting;
+  if (jwt !== undefined) {
+    const keys = ["secret", "public_key", "jwks_url"].filter((k) => jwt?.[k]);
+    if (keys.length !== 1) {
+      return ["jwt", "needs one of secret, public_key or jwks_url"];
+    }
+    if (jwt.jwks_url !== undefined && !/^https?:\/\//.test(jwt.jwks_url)) {
+      return ["jwt", "jwks_url must be an http(s) url"];
+    }
+  }
+}
+
+// project files are synced live, so only plain http(s) upstreams: docker,
+// k8s, auto and commands are set up once at startup from the main config
+function projectHostError(setting) {
+  const error = hostError(setting);
+  if (error) return error;
+  if (!/^https?:\/\//.test(setting.url)) {
+    return ["url", "must be http(s) in a project file"];
+  }
+  if (setting.command !== undefined) {
+    return ["command", "is only supported in the main config"];
+  }
+}
+
+// parse a yaml file, exiting on syntax errors; fail() reports further
+// problems as path:line:col at the position at() finds for a key path,
+// followed by that line with a caret under the column as yaml prints
+// for syntax errors
+function parseConfig(path, file) {
+  const lineCounter = new LineCounter();
+  const doc = parseDocument(file, { lineCounter });
+  const lines = file.split(/\r?\n/);
+  const fail = (message, { line, col } = { line: 1, col: 1 }) => {
+    const source = lines[line - 1] ?? "";
+    // tabs are kept so the caret lines up however they are shown
+    const indent = source.slice(0, col - 1).replace(/[^\t]/g, " ");
+    exit(
+      EXIT.CONFIG,
+      `${path}:${line}:${col}: ${message}\n\n${source}\n${indent}^`,
+    );
+  };
+  const at = (keys) => {
+    const node = doc.getIn(keys, true);
+    return node?.range && lineCounter.linePos(node.range[0]);
+  };
+
+  // yaml's own message already ends with the snippet
+  const [error] = doc.errors;
+  if (error) {
+    const { line, col } = error.linePos?.[0] ?? { line: 1, col: 1 };
+    exit(EXIT.CONFIG, `${path}:${line}:${col}: ${error.message}`);
+  }
+  return { value: doc.toJS() ?? {}, fail, at };
+}
+
+// exit on the first enabled host entry with a problem
+function checkHosts({ fail, at }, hosts, keys, error = hostError) {
+  for (const [key, setting] of Object.entries(hosts ?? {})) {
+    if (setting?.enabled === false) continue;
+    const [field, message] = error(setting) ?? [];
+    if (field) {
+      fail(
+        `hosts.${key}.${field} ${message}`,
+        at([...keys, key, field]) ?? at([...keys, key]),
+      );
+    }
+  }
+}
+
+// parse and validate the config and the project files it names,
+// reporting errors as path:line:col
+function loadConfig(path, file) {
+  const parsed = parseConfig(path, file);
+  const { fail, at } = parsed;
+
+  const { sptth } = parsed.value;
+  if (sptth?.config === undefined) fail("sptth.config is required");
+  checkHosts(parsed, sptth.hosts, ["sptth", "hosts"]);
+  // one catch-all only, a second one would never be reached
+  const [first, second] = Object.entries(sptth.hosts ?? {}).filter(
+    ([, setting]) => setting?.default && setting.enabled !== false,
+  );
+  if (second) {
+    fail(
+      `hosts.${second[0]}.default is already set by ${first[0]}`,
+      at(["sptth", "hosts", second[0], "default"]),
+    );
+  }
+  for (const name of ["max_connections", "max_connections_per_ip"]) {
+    const limit = sptth[name];
+    if (limit !== undefined && !(Number.isInteger(limit) && limit > 0)) {
+      fail(`${name} must be a positive number`, at(["sptth", name]));
+    }
+  }
+  for (const [name, ms] of Object.entries(sptth.timeouts ?? {})) {
+    if (!Number.isInteger(ms) || ms <= 0) {
+      fail(
+        `timeouts.${name} must be a positive number of milliseconds`,
+        at(["sptth", "timeouts", name]),
+      );
+    }
+  }
+  for (const [i, template] of (sptth.templates ?? []).entries()) {
+    const where = at(["sptth", "templates", i]);
+    for (const field of ["base", "key", "cert"]) {
+      if (typeof template?.[field] !== "string") {
+        fail(`templates[${i}].${field} is required`, where);
+      }
+    }
+    if (!Array.isArray(template.domains)) {
+      fail(`templates[${i}].domains must be a list`, where);
+    }
+    for (const domain of template.domains) {
+      if (!Number.isInteger(template.port_map?.[domain])) {
+        fail(
+          `templates[${i}].port_map.${domain} must be a port number`,
+          at(["sptth", "templates", i, "port_map"]) ?? where,
+        );
+      }
+    }
+  }
+  if (sptth.compose) {
+    for (const field of ["file", "base", "key", "cert"]) {
+      if (typeof sptth.compose[field] !== "string") {
+        fail(`compose.${field} is required`, at(["sptth", "compose"]));
+      }
+    }
+    if (!existsSync(resolve(sptth.compose.file))) {
+      fail(
+        `compose.file ${sptth.compose.file} does not exist`,
+        at(["sptth", "compose", "file"]),
+      );
+    }
+  }
+  // a project without a sptth.yaml (yet) declares nothing, but its
+  // directory has to exist to be watched
+  for (const [i, dir] of (sptth.projects ?? []).entries()) {
+    if (!existsSync(String(dir))) {
+      fail(
+        `projects[${i}] ${dir} does not exist`,
+        at(["sptth", "projects", i]),
+      );
+    }
+    const project = join(dir, "sptth.yaml");
+    if (!existsSync(project)) continue;
+    const text = readFileSync(project, { encoding: "utf-8" });
+    const projectConfig = parseConfig(project, text);
+    const { hosts } = projectConfig.value;
+    checkHosts(projectConfig, hosts, ["hosts"], projectHostError);
+  }
+  return sptth;
+}
+
+// TLDs reserved for testing and local use (RFC 2606, RFC 6761)
+const RESERVED_TLDS = ["test", "example", "invalid", "localhost", "internal"];
+
+// resolves true if something accepts tcp connections on the url's port
+function listening(url) {
+  const { protocol, hostname, port } = new URL(url);
+  return new Promise((resolve) => {
+    const socket = connect({
+      host: hostname.replace(/^\[(.*)\]$/, "$1"), // [::1] => ::1
+      port: port || (protocol === "https:" ? 443 : 80),
+      timeout: 1000,
+    });
+    const done = (result) => {
+      socket.destroy();
+      resolve(result);
+    };
+    socket.on("connect", () => done(true));
+    socket.on("error", () => done(false));
+    socket.on("timeout", () => done(false));
+  });
+}
+
+// warn about common dev-domain pitfalls, exit code 1 if any
+async function check(hosts) {
+  let warnings = 0;
+  const warn = (host, message) => {
+    warnings++;
+    console.log(`warn: ${host}: ${message}`);
+  };
+
+  for (const [host, setting] of Object.entries(hosts)) {
+    const tld = host.split(".").at(-1);
+    if (tld === "dev") {
+      warn(host, ".dev is HSTS preloaded and a public TLD");
+    } else if (tld === "local") {
+      warn(host, ".local is reserved for mDNS and may never reach sptth");
+    } else if (!RESERVED_TLDS.includes(tld)) {
+      warn(host, `.${tld} is not a reserved TLD, this may be a real domain`);
+    }
+    for (const field of ["key", "cert"]) {
+      await access(setting[field]).catch(() =>
+        warn(host, `${field} ${setting[field]} does not exist`),
+      );
+    }
+    if (/^https?:/.test(setting.url) && !(await listening(setting.url))) {
+      warn(host, `nothing is listening on ${setting.url}`);
+    }
+  }
+
+  console.log(warnings === 0 ? "ok" : `${warnings} warning(s)`);
+  return warnings === 0 ? 0 : 1;
+}
+
+// system roots plus the mkcert root, if mkcert is installed
+async function trustedCAs() {
+  try {
+    const caroot = execFileSync("mkcert", ["-CAROOT"], { encoding: "utf-8" });
+    const root = await readFile(join(caroot.trim(), "rootCA.pem"));
+    return [...rootCertificates, root];
+  } catch {
+    return [...rootCertificates];
+  }
+}
+
+// dns => tls => https against a running sptth, reporting each stage
+async function smoke(host, port = 443) {
+  const ca = await trustedCAs();
+  const stage = async (name, fn) => {
+    try {
+      console.log(`ok   ${name}: ${await fn()}`);
+    } catch (err) {
+      console.log(`fail ${name}: ${err.message}`);
+      process.exit(1);
+    }
+  };
+
+  await stage("dns", async () => (await lookup(host)).address);
+  await stage(
+    "tls",
+    () =>
+      new Promise((resolve, reject) => {
+        const socket = tlsConnect({ host, port, servername: host, ca }, () => {
+          const { subjectaltname, issuer } = socket.getPeerCertificate();
+          socket.end();
+          resolve(`${subjectaltname} issued by ${issuer.CN}`);
+        });
+        socket.on("error", reject);
+      }),
+  );
+  await stage(
+    "https",
+    () =>
+      new Promise((resolve, reject) => {
+        get(`https://${host}:${port}/`, { ca }, (res) => {
+          res.resume();
+          resolve(`${res.statusCode} ${res.statusMessage}`);
+        }).on("error", reject);
+      }),
+  );
+}
+
+// served for html navigations while the upstream is down; polls the same
+// url and reloads as soon as the proxy stops answering 502
+const OUTAGE_PAGE = `<!doctype html>
+<meta charset="utf-8" />
+<title>waiting for upstream</title>
+<p>upstream is not responding, retrying...</p>
+<script>
+  setInterval(async () => {
+    const res = await fetch(location.href, { method: "HEAD" });
+    if (res.status !== 502) location.reload();
+  }, 1000);
+</script>
+`;
+
+// launch the default browser
+function openBrowser(url) {
+  const [command, ...args] =
+    process.platform === "darwin"
+      ? ["open"]
+      : process.platform === "win32"
+        ? ["cmd", "/c", "start", '""']
+        : ["xdg-open"];
+  // without a desktop (ssh, containers) there may be no opener at all
+  spawn(command, [...args, url], { stdio: "ignore", detached: true })
+    .on("error", (err) =>
+      console.log(`warn: cannot open ${url}: ${err.message}`),
+    )
+    .unref();
+}
+
+// windows toast through powershell's own app id, which is registered and
+// so allowed to show toasts; the message comes in through the environment
+const TOAST = `
+$app = '{1AC14E77-02E7-4E5D-B744-2EB1AE5198B7}\\WindowsPowerShell\\v1.0\\powershell.exe'
+$manager = [Windows.UI.Notifications.ToastNotificationManager, Windows.UI.Notifications, ContentType = WindowsRuntime]
+$xml = $manager::GetTemplateContent([Windows.UI.Notifications.ToastTemplateType]::ToastText02)
+$text = $xml.GetElementsByTagName('text')
+$text[0].AppendChild($xml.CreateTextNode('sptth')) > $null
+$text[1].AppendChild($xml.CreateTextNode($env:SPTTH_MESSAGE)) > $null
+$manager::CreateToastNotifier($app).Show([Windows.UI.Notifications.ToastNotification]::new($xml))
+`;
+
+// desktop notification, best effort (no-op where no notifier exists)
+function notify(message) {
+  const script = `display notification ${JSON.stringify(message)} with title "sptth"`;
+  const [command, ...args] =
+    process.platform === "darwin"
+      ? ["osascript", "-e", script]
+      : process.platform === "win32"
+        ? ["powershell", "-NoProfile", "-NonInteractive", "-Command", TOAST]
+        : ["notify-send", "sptth", message];
+  const env = { ...process.env, SPTTH_MESSAGE: message };
+  spawn(command, args, { stdio: "ignore", env }).on("error", () => {});
+}
+
+// what a report can tell about this run, filled in during startup
+const diagnostics = { argv: process.argv.slice(2) };
+
+// the last RECENT_EVENTS lines sptth printed and redbird logged, at
+// every level whatever the console shows, so a report has the context
+// that led up to it
+const RECENT_EVENTS = 500;
+diagnostics.recent = [];
+
+function remember(level, msg) {
+  diagnostics.recent.push({ time: Date.now(), level, msg });
+  if (diagnostics.recent.length > RECENT_EVENTS) diagnostics.recent.shift();
+}
+
+// everything sptth reports itself (slow requests, grep, multipart,
+// caching, docker and kubectl errors, refusals) goes through these two
+for (const [name, level] of [
+  ["log", "stdout"],
+  ["error", "stderr"],
+]) {
+  const write = console[name];
+  console[name] = (...args) => {
+    remember(level, format(...args));
+    write(...args);
+  };
+}
+
+// a url without its user:password@, anything else (url: auto) as is
+function redact(url) {
+  try {
+    const parsed = new URL(url);
+    parsed.username = "";
+    parsed.password = "";
+    return parsed.href;
+  } catch {
+    return url;
+  }
+}
+
+// report to attach to bug reports: versions, the error if any and the
+// routes, leaving out hooks and commands as they may carry credentials
+function writeReport(kind, err) {
+  const pkg = new URL("./package.json", import.meta.url);
+  const { version } = JSON.parse(readFileSync(pkg, { encoding: "utf-8" }));
+  const report = {
+    time: new Date().toISOString(),
+    version,
+    node: process.versions.node,
+    platform: `${process.platform} ${process.arch}`,
+    ...diagnostics,
+    error: err?.stack,
+  };
+  const file = join(tmpdir(), `sptth-${kind}-${Date.now()}.json`);
+  writeFileSync(file, JSON.stringify(report, null, 2));
+  return file;
+}
+
+// loopback server for requests sptth answers itself: respond() keeps
+// the answer under a random id and returns the url a resolver routes to,
+// /<id> with the original path appended after it. Each answer is served
+// once, unclaimed ones are dropped after a minute, and any other path
+// is a 404, so nothing in the url is trusted
+async function startResponder() {
+  const pending = new Map();
+  const server = createHttpServer((req, res) => {
+    const id = req.url.split("/")[1];
+    const { status, detail, headers } = pending.get(id) ?? {
+      status: 404,
+      detail: "no such response",
+    };
+    pending.delete(id);
+    res.writeHead(status, { "content-type": "text/plain", ...headers });
+    res.end(`${status} ${STATUS_CODES[status] ?? ""}\n${detail}\n`);
+  });
+  await new Promise((resolve) => server.listen(0, "127.0.0.1", resolve));
+  const { port } = server.address();
+  return (status, detail, headers = {}) => {
+    const id = randomUUID();
+    pending.set(id, { status, detail, headers });
+    setTimeout(() => pending.delete(id), 60000).unref();
+    return `http://127.0.0.1:${port}/${id}`;
+  };
+}
+
+// run a hook: POST the event as json to a url, or run a shell command
+// with the event in SPTTH_EVENT and SPTTH_DATA
+function runHook(target, event, data) {
+  if (/^https?:\/\//.test(target)) {
+    const body = JSON.stringify({ event, ...data });
+    fetch(target, {
+      method: "POST",
+      headers: { "content-type": "application/json" },
+      body,
+    }).catch((err) => console.error(`hook ${event}: ${err.message}`));
+  } else {
+    const env = {
+      ...process.env,
+      SPTTH_EVENT: event,
+      SPTTH_DATA: JSON.stringify(data),
+    };
+    spawn(target, { shell: true, stdio: "inherit", env });
+  }
+}
+
+// processes started for hosts (dev servers, kubectl port-forwards),
+// stopped together with sptth
+const children = new Set();
+
+// the shell's process group, so `npm run dev` takes its node with it
+function killGroup(pid) {
+  try {
+    process.kill(process.platform === "win32" ? pid : -pid);
+  } catch {}
+}
+
+function stopChildren() {
+  if (children.size === 0) return;
+  for (const child of children) {
+    children.delete(child);
+    killGroup(child.pid);
+  }
+  saveChildren();
+}
+
+// the children's process groups of every running sptth, by its pid, so
+// the groups of one that could not stop them (SIGKILL, a crash of node
+// itself) are stopped on the next start; groups recorded before the last
+// boot are dropped, their ids may belong to anything by now
+const CHILDREN_FILE = join(tmpdir(), "sptth-children.json");
+const startedAt = Date.now() - process.uptime() * 1000;
+
+function readChildren() {
+  try {
+    return JSON.parse(readFileSync(CHILDREN_FILE, { encoding: "utf-8" }));
+  } catch {
+    return {};
+  }
+}
+
+function writeChildren(owners) {
+  try {
+    writeFileSync(CHILDREN_FILE, JSON.stringify(owners));
+  } catch (err) {
+    console.error(`${CHILDREN_FILE}: ${err.message}`);
+  }
+}
+
+function saveChildren() {
+  const owners = readChildren();
+  const pgids = [...children].map((child) => child.pid).filter(Boolean);
+  if (pgids.length > 0) owners[process.pid] = { since: startedAt, pgids };
+  else delete owners[process.pid];
+  writeChildren(owners);
+}
+
+function reapChildren() {
+  const boot = Date.now() - uptime() * 1000;
+  const alive = (pid) => {
+    try {
+      return process.kill(pid, 0);
+    } catch (err) {
+      return err.code === "EPERM";
+    }
+  };
+  const owners = readChildren();
+  for (const [owner, { since, pgids }] of Object.entries(owners)) {
+    if (since < boot) {
+      delete owners[owner];
+    } else if (!alive(Number(owner))) {
+      console.log(
+        `stopping ${pgids.length} process(es) left by sptth ${owner}`,
+      );
+      pgids.forEach(killGroup);
+      delete owners[owner];
+    }
+  }
+  writeChildren(owners);
+}
+
+// copy a child's output into ours line by line, tagged with the host
+function prefixLines(stream, out, key, onLine) {
+  let rest = "";
+  stream.setEncoding("utf-8").on("data", (chunk) => {
+    const lines = (rest + chunk).split("\n");
+    rest = lines.pop();
+    for (const line of lines) {
+      out.write(`[${key}] ${line}\n`);
+      onLine?.(line);
+    }
+  });
+}
+
+// run a host's command, restarting it after a crash; onStart() is called
+// on every start and returns the output line handler for that run
+function supervise(key, setting, onStart) {
+  const onLine = onStart?.();
+  const child = spawn(setting.command, {
+    shell: true,
+    cwd: setting.cwd,
+    detached: process.platform !== "win32",
+    stdio: ["ignore", "pipe", "pipe"],
+  });
+  children.add(child);
+  saveChildren();
+  prefixLines(child.stdout, process.stdout, key, onLine);
+  prefixLines(child.stderr, process.stderr, key, onLine);
+  // the shell itself could not start, a restart would fail the same way
+  child.on("error", (err) => {
+    if (children.delete(child)) saveChildren();
+    console.error(`[${key}] cannot start ${setting.command}: ${err.message}`);
+  });
+  child.on("exit", (code, signal) => {
+    if (!children.delete(child)) return; // stopped by us
+    saveChildren();
+    if (code === 0) return console.log(`[${key}] exited`);
+    console.log(`[${key}] exited with ${signal ?? code}, restarting`);
+    setTimeout(() => supervise(key, setting, onStart), 1000);
+  });
+  return child;
+}
+
+// exit on signals too, so the exit handler gets to stop the children
+process.on("exit", stopChildren);
+process.on("SIGINT", () => process.exit(130));
+process.on("SIGTERM", () => process.exit(143));
+process.on("SIGHUP", () => process.exit(129)); // terminal closed
+
+// hosts declared in a project's own sptth.yaml, key/cert relative to it;
+// a missing file declares nothing
+async function projectHosts(dir) {
+  const file = await readFile(join(dir, "sptth.yaml"), {
+    encoding: "utf-8",
+  }).catch(() => "");
+  const { hosts = {} } = parse(file) ?? {};
+  const result = {};
+  for (const [key, setting] of Object.entries(hosts)) {
+    if (setting?.enabled === false) continue;
+    const [field, message] = projectHostError(setting) ?? [];
+    if (field) {
+      console.log(`warn: ${dir}: hosts.${key}.${field} ${message}, skipped`);
+      continue;
+    }
+    result[normalizeHost(key)] = {
+      ...setting,
+      key: resolve(dir, setting.key),
+      cert: resolve(dir, setting.cert),
+    };
+  }
+  return result;
+}
+
+// count patterns in a response body as it streams past to the client,
+// e.g. hardcoded http://localhost:3000 urls that break behind https
+function grepBody(proxyRes, patterns, report) {
+  const decoders = {
+    gzip: createGunzip,
+    br: createBrotliDecompress,
+    deflate: createInflate,
+  };
+  const decode = decoders[proxyRes.headers["content-encoding"]];
+  const body = decode ? decode() : new PassThrough();
+  const counts = Object.fromEntries(patterns.map((pattern) => [pattern, 0]));
+  const keep = Math.max(...patterns.map((pattern) => pattern.length)) - 1;
+  const count = (text, pattern) => text.split(pattern).length - 1;
+
+  // the tail of the previous chunk catches matches across chunks,
+  // matches entirely inside it were counted already
+  let tail = "";
+  body.setEncoding("utf-8");
+  body.on("data", (chunk) => {
+    const text = tail + chunk;
+    for (const pattern of patterns) {
+      counts[pattern] += count(text, pattern) - count(tail, pattern);
+    }
+    tail = keep > 0 ? text.slice(-keep) : "";
+  });
+  body.on("end", () => report(counts));
+  body.on("error", () => {});
+  proxyRes.on("data", (chunk) => body.write(chunk));
+  proxyRes.on("end", () => body.end());
+}
+
+// anything unexpected: crash report, then exit
+process.on("uncaughtException", (err) => {
+  const report = writeReport("crash", err);
+  exit(EXIT.SOFTWARE, `${err.stack}\ncrash report written to ${report}`);
+});
+
+// SIGQUIT (ctrl-\) writes the same report from a running sptth, recent
+// events included, and keeps serving
+process.on("SIGQUIT", () => {
+  console.log(`report written to ${writeReport("report")}`);
+});
+
 // CLI option
 const arg = process.argv.at(2);
 
-if (arg === undefined || arg.startsWith("-")) {
+if (arg === "version" || arg === "--version") {
+  version(await readPackage(), process.argv.includes("--json"));
+  process.exit(0);
+}
+
+if (arg === "test") {
+  const [host, port] = process.argv.slice(3);
+  if (host === undefined) help();
+  await smoke(normalizeHost(host), port);
+  process.exit(0);
+}
+
+if (arg === "self-update") {
+  process.exit(await selfUpdate(process.argv.includes("--check")));
+}
+
+const [command, path] =
+  arg === "check" ? ["check", process.argv.at(3)] : ["run", arg];
+
+if (path === undefined || path.startsWith("-")) {
   help();
 }
 
-const yaml = resolve(arg);
-const file = await readFile(yaml, { encoding: "utf-8" });
-const { hosts, config } = parse(file).sptth;
+const yaml = resolve(path);
+const file = await readFile(yaml, { encoding: "utf-8" }).catch((err) =>
+  exit(EXIT.CONFIG, err.message),
+);
+const {
+  hosts = {},
+  templates = [],
+  config,
+  compose,
+  localhost_ports,
+  outage_page,
+  notifications,
+  index,
+  sni_mismatch,
+  hooks = {},
+  projects = [],
+  slow_ms,
+  timeouts = {},
+  max_connections,
+  max_connections_per_ip,
+} = loadConfig(yaml, file);
+
+// key hosts by their canonical form
+for (const [key, setting] of Object.entries(hosts)) {
+  delete hosts[key];
+  hosts[normalizeHost(key)] = setting;
+}
+
+// expand templates into hosts, explicit hosts take precedence
+for (const { base, domains, port_map, key, cert } of templates) {
+  for (const domain of domains) {
+    hosts[normalizeHost(`${domain}.${base}`)] ??= {
+      key,
+      cert,
+      url: `http://127.0.0.1:${port_map[domain]}`,
+    };
+  }
+}
+
+// disabled hosts stay in the file but are never registered
+for (const [key, setting] of Object.entries(hosts)) {
+  if (setting.enabled === false) delete hosts[key];
+}
+
+diagnostics.config = yaml;
+diagnostics.hosts = Object.fromEntries(
+  Object.entries(hosts).map(([key, setting]) => [key, redact(setting.url)]),
+);
+
+if (command === "check") {
+  process.exit(await check(hosts));
+}
+
+reapChildren();
 
 const proxy = redbird(config);
 
-console.log(`starting reverse https proxy on port ${config.port}`);
+// redbird only logs listen errors on its servers and keeps running
+for (const server of [proxy.server, proxy.httpsServer]) {
+  server?.on("error", (err) => {
+    if (["EADDRINUSE", "EACCES"].includes(err.code)) {
+      exit(EXIT.BIND, err.message);
+    }
+  });
+}
+
+// redbird's log calls go to the recent events too; pino replaces its
+// level methods on every level change (disabled ones with a noop), so
+// they are wrapped again after each
+function recordLog(log) {
+  for (const level of ["trace", "debug", "info", "warn", "error", "fatal"]) {
+    const write = log[level];
+    log[level] = function (...args) {
+      remember(level, logMessage(args));
+      return write.apply(this, args);
+    };
+  }
+}
+
+// the message of a pino call, (msg, ...args) or (obj, msg, ...args);
+// the objects may hold live sockets, so they are not kept
+function logMessage([first, ...rest]) {
+  if (typeof first === "string") return format(first, ...rest);
+  if (typeof rest[0] === "string") return format(...rest);
+  return first instanceof Error ? first.message : "";
+}
 
-Object.entries(hosts).forEach(([key, setting]) => {
-  console.log(`> https://${key} => ${setting.url}`);
-  proxy.register(key, setting.url, {
+// SIGUSR1 switches redbird's pino log to debug, SIGUSR2 restores the level
+if (proxy.log) {
+  const { level } = proxy.log;
+  const setLevel = (next) => {
+    proxy.log.level = next;
+    recordLog(proxy.log);
+  };
+  recordLog(proxy.log);
+  process.on("SIGUSR1", () => setLevel("debug"));
+  process.on("SIGUSR2", () => setLevel(level));
+}
+
+// client timeouts on redbird's listeners, node's defaults where unset;
+// a socket idle for idle_ms is destroyed
+for (const server of [proxy.server, proxy.httpsServer]) {
+  if (server === undefined) continue;
+  const { headers_ms, request_ms, keep_alive_ms, idle_ms } = timeouts;
+  if (headers_ms) server.headersTimeout = headers_ms;
+  if (request_ms) server.requestTimeout = request_ms;
+  if (keep_alive_ms) server.keepAliveTimeout = keep_alive_ms;
+  if (idle_ms) server.setTimeout(idle_ms);
+}
+
+// connection limits on redbird's listeners: node closes connections past
+// maxConnections itself, the per-ip count is kept here per listener
+for (const server of [proxy.server, proxy.httpsServer]) {
+  if (server === undefined) continue;
+  if (max_connections) server.maxConnections = max_connections;
+  if (!max_connections_per_ip) continue;
+  const open = new Map();
+  const refusing = new Set();
+  server.on("connection", (socket) => {
+    const { remoteAddress } = socket;
+    const count = open.get(remoteAddress) ?? 0;
+    if (count >= max_connections_per_ip) {
+      // logged once until the address is below the limit again,
+      // a load test would flood the console otherwise
+      if (!refusing.has(remoteAddress)) {
+        console.log(`refusing connections from ${remoteAddress}`);
+        refusing.add(remoteAddress);
+      }
+      return socket.destroy();
+    }
+    open.set(remoteAddress, count + 1);
+    socket.on("close", () => {
+      const left = open.get(remoteAddress) - 1;
+      refusing.delete(remoteAddress);
+      if (left === 0) open.delete(remoteAddress);
+      else open.set(remoteAddress, left);
+    });
+  });
+}
+
+// replace redbird's upstream error handler to serve the outage page
+if (outage_page) {
+  proxy.proxy.removeAllListeners("error");
+  proxy.proxy.on("error", (err, req, res) => {
+    // websocket upgrades hand us the raw socket
+    if (res.writeHead === undefined) return res.destroy();
+    if (res.headersSent) return res.end();
+    const html = req.method === "GET" && req.headers.accept?.includes("html");
+    res.writeHead(502, { "content-type": html ? "text/html" : "text/plain" });
+    res.end(html ? OUTAGE_PAGE : err.code);
+  });
+}
+
+function emit(event, data) {
+  const target = hooks[`on_${event}`];
+  if (target) runHook(target, event, data);
+}
+
+// report once when a host's upstream fails and once when it recovers
+const down = new Set();
+proxy.proxy.on("error", (err, req) => {
+  const host = normalizeHost(req.headers.host);
+  if (down.has(host)) return;
+  down.add(host);
+  if (notifications) notify(`upstream for ${host} went down (${err.code})`);
+  emit("upstream_down", { host, error: err.code });
+});
+proxy.proxy.on("proxyRes", (res, req) => {
+  const host = normalizeHost(req.headers.host);
+  if (down.delete(host) && notifications) {
+    notify(`upstream for ${host} is back`);
+  }
+});
+
+// log requests slower than slow_ms with where the time went:
+// waiting for upstream response headers, then streaming the body
+if (slow_ms) {
+  proxy.proxy.on("proxyReq", (proxyReq, req) => {
+    req.proxyStart = performance.now();
+  });
+  proxy.proxy.on("proxyRes", (proxyRes, req, res) => {
+    const headers = performance.now() - req.proxyStart;
+    const { remoteAddress, remotePort } = proxyRes.socket;
+    res.on("finish", () => {
+      const total = performance.now() - req.proxyStart;
+      if (total < slow_ms) return;
+      console.log(
+        `slow ${req.method} https://${req.headers.host}${req.url}` +
+          ` => ${remoteAddress}:${remotePort} ${proxyRes.statusCode}` +
+          ` total=${total.toFixed()}ms headers=${headers.toFixed()}ms` +
+          ` body=${(total - headers).toFixed()}ms`,
+      );
+    });
+  });
+}
+
+// current upstream and setting of each registered host
+const targets = {};
+const settings = {};
+
+// route changes are logged once the startup summary is out
+let started = false;
+
+function register(key, setting, url) {
+  if (started) console.log(`> https://${key} => ${url}`);
+  targets[key] = url;
+  settings[key] = setting;
+  proxy.register(key, url, {
     ssl: {
       key: setting.key,
       cert: setting.cert,
     },
   });
+}
+
+function unregister(key) {
+  if (started) console.log(`x https://${key}`);
+  delete targets[key];
+  delete settings[key];
+  proxy.unregister(key);
+}
+
+// report configured patterns found in text responses of a host
+proxy.proxy.on("proxyRes", (proxyRes, req) => {
+  const host = normalizeHost(req.headers.host);
+  const patterns = settings[host]?.grep;
+  const type = proxyRes.headers["content-type"] ?? "";
+  if (!patterns?.length || !/html|json|javascript|css/.test(type)) return;
+  grepBody(proxyRes, patterns, (counts) => {
+    const found = Object.entries(counts).filter(([, n]) => n > 0);
+    if (found.length === 0) return;
+    const summary = found.map(([pattern, n]) => `"${pattern}" x${n}`);
+    console.log(`grep https://${host}${req.url}: ${summary.join(", ")}`);
+  });
+});
+
+// requests sptth refuses itself are routed to the responder
+const respond = await startResponder();
+
+// log responses that ignore a Range or conditional request, for hosts
+// with validate_caching; the headers themselves pass through untouched
+proxy.proxy.on("proxyRes", (proxyRes, req) => {
+  const host = normalizeHost(req.headers.host);
+  if (!settings[host]?.validate_caching || proxyRes.statusCode !== 200) return;
+  const { range, "if-none-match": ifNoneMatch } = req.headers;
+  const { etag, "accept-ranges": acceptRanges } = proxyRes.headers;
+  const url = `https://${host}${req.url}`;
+  if (range && acceptRanges === "bytes") {
+    console.log(`caching ${url}: Range ${range} answered 200, expected 206`);
+  }
+  if (ifNoneMatch && etag && ifNoneMatch.split(/\s*,\s*/).includes(etag)) {
+    console.log(
+      `caching ${url}: If-None-Match ${etag} answered 200, expected 304`,
+    );
+  }
+});
+
+// server-side cookie jars for cookie_jar hosts, so scripted clients keep
+// a login by naming a jar in x-sptth-jar instead of managing cookie
+// files; kept in memory only, and every cookie of a jar is sent
+// regardless of its path
+const jars = new Map();
+
+function jarOf(req) {
+  const host = normalizeHost(req.headers.host);
+  const name = req.headers["x-sptth-jar"];
+  if (!name || !settings[host]?.cookie_jar) return;
+  const id = `${host} ${name}`;
+  if (!jars.has(id)) jars.set(id, new Map());
+  return jars.get(id);
+}
+
+proxy.proxy.on("proxyReq", (proxyReq, req) => {
+  const jar = jarOf(req);
+  if (jar === undefined) return;
+  proxyReq.removeHeader("x-sptth-jar");
+  // cookies the client sends itself win over the stored ones
+  const sent = req.headers.cookie ?? "";
+  const names = sent.split(";").map((cookie) => cookie.split("=")[0].trim());
+  const stored = [...jar]
+    .filter(([name]) => !names.includes(name))
+    .map(([name, value]) => `${name}=${value}`);
+  if (stored.length === 0) return;
+  proxyReq.setHeader("cookie", [sent, ...stored].filter(Boolean).join("; "));
 });
+
+proxy.proxy.on("proxyRes", (proxyRes, req) => {
+  const jar = jarOf(req);
+  if (jar === undefined) return;
+  for (const cookie of proxyRes.headers["set-cookie"] ?? []) {
+    const [pair, ...attributes] = cookie.split(";");
+    const [name, value] = pair.split(/=(.*)/).map((part) => part.trim());
+    const expired = attributes.some((attribute) => {
+      const [key, arg] = attribute.split(/=(.*)/).map((part) => part.trim());
+      return (
+        (/^max-age$/i.test(key) && Number(arg) <= 0) ||
+        (/^expires$/i.test(key) && Date.parse(arg) < Date.now())
+      );
+    });
+    if (expired) jar.delete(name);
+    else jar.set(name, value ?? "");
+  }
+});
+
+// log the fields and file sizes of multipart uploads to log_multipart
+// hosts; the parser is attached when redbird pipes the request upstream,
+// so it sees the same bytes without consuming any early
+const multipartResolver = (host, url, req) => {
+  const setting = settings[normalizeHost(host)];
+  const type = req.headers["content-type"] ?? "";
+  const boundary = type.match(/^multipart\/form-data;.*boundary="?([^";]+)/i);
+  if (!setting?.log_multipart || !boundary) return;
+  const { write, parts } = multipartParts(boundary[1]);
+  const pipe = req.pipe;
+  req.pipe = (...args) => {
+    req.on("data", write);
+    req.on("end", () => {
+      const fields = parts.map(({ name, filename, size }) =>
+        filename === undefined
+          ? `${name} (${size} bytes)`
+          : `${name}=${filename} (${size} bytes)`,
+      );
+      console.log(`multipart https://${host}${url}: ${fields.join(", ")}`);
+    });
+    return pipe.apply(req, args);
+  };
+};
+multipartResolver.priority = 200;
+proxy.addResolver(multipartResolver);
+
+// redbird looks routes up by the Host header as sent, so a spelling like
+// App.Example. reaches the host it normalizes to through this resolver
+const normalizedResolver = (host) => {
+  const key = normalizeHost(host);
+  if (key !== host) return targets[key];
+};
+normalizedResolver.priority = 10;
+proxy.addResolver(normalizedResolver);
+
+// gates: checks that may refuse a request with a responder url, run in
+// order by gateResolver below
+
+// refuse requests routed by Host to a different name than the TLS
+// handshake was for, e.g. curl --resolve pointing at the wrong name
+const sniGate = (host, url, req) => {
+  const sni = req.socket.servername;
+  if (sni_mismatch && sni && normalizeHost(sni) !== normalizeHost(host)) {
+    return respond(421, `Host ${host} does not match TLS server name ${sni}`);
+  }
+};
+
+// per-host client address allow/deny lists, checked on the socket address
+const aclGate = (host, url, req) => {
+  const setting = settings[normalizeHost(host)];
+  if (!setting?.allow && !setting?.deny) return;
+  const { remoteAddress } = req.socket;
+  if (!permitted(setting, remoteAddress)) {
+    return respond(403, `${remoteAddress} is not allowed to reach ${host}`);
+  }
+};
+
+// per-host method allowlist and request header limits, like a basic WAF
+const policyGate = (host, url, req) => {
+  const setting = settings[normalizeHost(host)];
+  if (setting === undefined) return;
+  const { allowed_methods, max_header_count, max_header_bytes } = setting;
+  const methods = allowed_methods?.map((method) => method.toUpperCase());
+  if (methods && !methods.includes(req.method)) {
+    const allow = methods.join(", ");
+    return respond(405, `allowed methods: ${allow}`, { allow });
+  }
+  const count = req.rawHeaders.length / 2;
+  if (max_header_count && count > max_header_count) {
+    return respond(431, `${count} headers, at most ${max_header_count}`);
+  }
+  // "name: value\r\n" per header
+  const bytes = req.rawHeaders.reduce((sum, part) => sum + part.length + 2, 0);
+  if (max_header_bytes && bytes > max_header_bytes) {
+    return respond(431, `${bytes} header bytes, at most ${max_header_bytes}`);
+  }
+};
+
+// jwt hosts need a valid bearer token before the upstream is hit, like
+// an edge gateway: HS256/384/512 with a shared secret, RS256/384/512 with
+// a pem public key or the key of the token's kid from a jwks url; the
+// jwks is fetched once per url, and again after a failed fetch
+const JWT_HASHES = { 256: "sha256", 384: "sha384", 512: "sha512" };
+const jwks = new Map();
+
+function jwksKeys(url) {
+  if (!jwks.has(url)) {
+    const keys = fetch(url)
+      .then((res) => {
+        if (!res.ok) throw new Error(`${res.status} ${res.statusText}`);
+        return res.json();
+      })
+      .then(({ keys }) => {
+        const entries = keys.map((jwk) => [
+          jwk.kid,
+          createPublicKey({ key: jwk, format: "jwk" }),
+        ]);
+        return new Map(entries);
+      });
+    keys.catch(() => jwks.delete(url));
+    jwks.set(url, keys);
+  }
+  return jwks.get(url);
+}
+
+// why a token is refused, undefined for a valid one
+async function jwtError(token, { secret, public_key, jwks_url, ...claims }) {
+  const [header, payload, signature = ""] = token.split(".");
+  const decode = (part) => JSON.parse(Buffer.from(part, "base64url"));
+  let alg, kid, body;
+  try {
+    ({ alg, kid } = decode(header));
+    body = decode(payload);
+  } catch {
+    return "malformed token";
+  }
+  if (typeof body !== "object" || body === null) return "malformed token";
+  const hash = JWT_HASHES[alg?.slice(2)];
+  const data = Buffer.from(`${header}.${payload}`);
+  const sig = Buffer.from(signature, "base64url");
+  if (secret !== undefined && alg?.startsWith("HS") && hash) {
+    const expected = createHmac(hash, String(secret)).update(data).digest();
+    const equal =
+      expected.length === sig.length && timingSafeEqual(expected, sig);
+    if (!equal) return "invalid signature";
+  } else if (secret === undefined && alg?.startsWith("RS") && hash) {
+    const key = jwks_url
+      ? (await jwksKeys(jwks_url)).get(kid)
+      : createPublicKey(await readFile(public_key));
+    if (key === undefined) return `no key ${kid} in ${jwks_url}`;
+    if (!verify(hash, data, key, sig)) return "invalid signature";
+  } else {
+    return `algorithm ${alg} is not accepted`;
+  }
+
+  const now = Date.now() / 1000;
+  if (body.exp !== undefined && now >= body.exp) return "token expired";
+  if (body.nbf !== undefined && now < body.nbf) return "token not yet valid";
+  if (claims.issuer !== undefined && body.iss !== claims.issuer) {
+    return `issuer ${body.iss} is not ${claims.issuer}`;
+  }
+  const audiences = [body.aud].flat();
+  if (claims.audience !== undefined && !audiences.includes(claims.audience)) {
+    return `audience ${body.aud} is not ${claims.audience}`;
+  }
+}
+
+const jwtGate = async (host, url, req) => {
+  const setting = settings[normalizeHost(host)];
+  if (!setting?.jwt) return;
+  const token = req.headers.authorization?.match(/^Bearer +(\S+)$/i)?.[1];
+  const error =
+    token === undefined
+      ? "bearer token required"
+      : await jwtError(token, setting.jwt).catch((err) => err);
+  if (error instanceof Error) {
+    return respond(502, `jwt keys for ${host}: ${error.message}`);
+  }
+  if (error !== undefined) {
+    const challenge =
+      token === undefined ? "Bearer" : `Bearer error="invalid_token"`;
+    return respond(401, error, { "www-authenticate": challenge });
+  }
+};
+
+// forward_auth hosts ask the auth url first, with the original headers
+// like nginx auth_request: a 2xx lets the request through with the
+// forward_auth_headers of that answer (or without them, so clients
+// cannot set them), anything else is returned to the client as is
+const HOP_HEADERS = [
+  "host",
+  "connection",
+  "keep-alive",
+  "transfer-encoding",
+  "upgrade",
+  "content-length",
+  "expect",
+  "te",
+  "trailer",
+];
+const authGate = async (host, url, req) => {
+  const setting = settings[normalizeHost(host)];
+  if (!setting?.forward_auth) return;
+  const headers = Object.fromEntries(
+    Object.entries(req.headers).filter(([name]) => !HOP_HEADERS.includes(name)),
+  );
+  const res = await fetch(setting.forward_auth, {
+    headers: {
+      ...headers,
+      "x-forwarded-method": req.method,
+      "x-forwarded-proto": "https",
+      "x-forwarded-host": host,
+      "x-forwarded-uri": url,
+    },
+    redirect: "manual",
+  }).catch((err) => err);
+  if (res instanceof Error) {
+    return respond(502, `forward auth ${setting.forward_auth}: ${res.message}`);
+  }
+  await res.body?.cancel();
+  if (!res.ok) {
+    // redirects to a login page and auth challenges reach the client
+    const passed = {};
+    for (const name of ["location", "www-authenticate"]) {
+      if (res.headers.has(name)) passed[name] = res.headers.get(name);
+    }
+    return respond(res.status, `denied by ${setting.forward_auth}`, passed);
+  }
+  for (const name of setting.forward_auth_headers ?? []) {
+    const value = res.headers.get(name);
+    if (value === null) delete req.headers[name.toLowerCase()];
+    else req.headers[name.toLowerCase()] = value;
+  }
+};
+
+// resolvers only get the request, the response is looked up here to act
+// once it is done; prepended so it runs before redbird's own handler
+const responses = new WeakMap();
+for (const server of [proxy.server, proxy.httpsServer]) {
+  server?.prependListener("request", (req, res) => responses.set(req, res));
+}
+
+// per-host concurrency: requests over max_concurrent_requests wait for a
+// slot in a queue of max_queued_requests, and get a 503 once it is full;
+// a finished or aborted response hands its slot to the next in line
+const slots = {};
+const concurrencyGate = async (host, url, req) => {
+  const key = normalizeHost(host);
+  const { max_concurrent_requests: limit, max_queued_requests = 100 } =
+    settings[key] ?? {};
+  const res = responses.get(req);
+  if (!limit || res === undefined) return; // websocket upgrades have no res
+  const slot = (slots[key] ??= { active: 0, queue: [] });
+  if (slot.active < limit) {
+    slot.active++;
+  } else if (slot.queue.length < max_queued_requests) {
+    // a client gone while queued leaves the line, and is never resolved
+    await new Promise((resolve) => {
+      slot.queue.push(resolve);
+      res.on("close", () => {
+        const index = slot.queue.indexOf(resolve);
+        if (index !== -1) slot.queue.splice(index, 1);
+      });
+    });
+  } else {
+    const busy = `${limit} concurrent and ${max_queued_requests} queued`;
+    return respond(503, `${key} is at its limit of ${busy} requests`);
+  }
+  res.on("close", () => {
+    const next = slot.queue.shift();
+    if (next) next();
+    else slot.active--;
+  });
+};
+
+// redbird calls all resolvers at once and only then takes the answer of
+// the highest priority, so separate resolvers would all run: forward_auth
+// asked and a concurrency slot taken for a request the acl refuses. The
+// gates run one after another here instead, and the first refusal wins
+const gates = [
+  sniGate,
+  aclGate,
+  policyGate,
+  jwtGate,
+  authGate,
+  concurrencyGate,
+];
+const gateResolver = async (host, url, req) => {
+  for (const gate of gates) {
+    const refusal = await gate(host, url, req);
+    if (refusal) return refusal;
+  }
+};
+gateResolver.priority = 100;
+proxy.addResolver(gateResolver);
+
+// convention routes need no per-port config; TLS falls back to config.ssl
+if (localhost_ports) {
+  const resolver = (host) => {
+    const port = normalizeHost(host).match(/(?:^|\.)(\d+)\.localhost$/)?.[1];
+    if (port) return `http://127.0.0.1:${port}`;
+  };
+  resolver.priority = -1;
+  proxy.addResolver(resolver);
+}
+
+// portal listing every host with upstream status, for unknown hosts
+if (index) {
+  const server = createHttpServer(async (req, res) => {
+    const items = await Promise.all(
+      Object.entries(targets).map(async ([key, url]) => {
+        const status = (await listening(url)) ? "up" : "down";
+        return `<li><a href="https://${key}/">${key}</a> ${url} (${status})</li>`;
+      }),
+    );
+    res.writeHead(404, { "content-type": "text/html" });
+    res.end(`<!doctype html>
+<meta charset="utf-8" />
+<title>sptth</title>
+<ul>
+${items.join("\n")}
+</ul>
+`);
+  });
+  server.listen(0, "127.0.0.1", () => {
+    const resolver = () => `http://127.0.0.1:${server.address().port}`;
+    resolver.priority = -3;
+    proxy.addResolver(resolver);
+  });
+}
+
+// open a host in the browser once redbird accepts connections
+const listener = proxy.httpsServer ?? proxy.server;
+const ready = listener.listening
+  ? Promise.resolve()
+  : new Promise((resolve) => listener.once("listening", resolve));
+const openWhenReady = (key) => ready.then(() => openBrowser(`https://${key}/`));
+
+// hosts by container name, several hosts may route to one container
+const containers = {};
+
+for (const [key, setting] of Object.entries(hosts)) {
+  let url = setting.url;
+  if (url.startsWith("docker://")) {
+    const { hostname } = new URL(url);
+    (containers[hostname] ??= []).push([key, setting]);
+    url = await resolveDocker(url).catch((err) =>
+      exit(EXIT.UPSTREAM, `${key}: ${err.message}`),
+    );
+  } else if (url.startsWith("k8s://")) {
+    url = await resolveK8s(key, url);
+  }
+  const open = setting.open || process.argv.includes("--open");
+  if (setting.command) {
+    // url: auto takes the first url the command announces after each
+    // start, later urls in its output (e.g. logged api calls) are ignored;
+    // the browser opens once the first url is known
+    const follow = () => {
+      let announced = false;
+      return (line) => {
+        const detected = !announced && announcedUrl(line);
+        if (!detected) return;
+        announced = true;
+        if (detected === targets[key]) return;
+        const first = targets[key] === undefined;
+        if (!first) unregister(key);
+        register(key, setting, detected);
+        if (first && open) openWhenReady(key);
+      };
+    };
+    supervise(key, setting, url === "auto" ? follow : undefined);
+  }
+  if (url !== "auto") register(key, setting, url);
+  // catch-all below the convention resolver for hosts nobody registered
+  if (setting.default) {
+    const resolver = () => targets[key];
+    resolver.priority = -2;
+    proxy.addResolver(resolver);
+  }
+  if (open && url !== "auto") openWhenReady(key);
+}
+
+// re-resolve when a container restarts with a new ip/port
+if (Object.keys(containers).length > 0) {
+  const filters = { type: ["container"], event: ["start"] };
+  dockerEvents(filters, ({ Actor }) => {
+    for (const [key, setting] of containers[Actor.Attributes.name] ?? []) {
+      // a failed re-resolve keeps the old route instead of crashing
+      resolveDocker(setting.url).then(
+        (url) => {
+          unregister(key);
+          register(key, setting, url);
+        },
+        (err) => console.error(`${key}: ${err.message}`),
+      );
+    }
+  });
+}
+
+// register the hosts load() returns and re-sync them whenever the file
+// changes; the directory is watched so the file may come and go. Hosts
+// already routed by the config or another file are skipped with a
+// warning, and only hosts this file registered are ever unregistered
+async function syncFile(file, load) {
+  let current = {};
+  const sync = async () => {
+    const loaded = await load();
+    const same = (key) =>
+      JSON.stringify(current[key]) === JSON.stringify(loaded[key]);
+    for (const key of Object.keys(current)) {
+      if (!same(key)) unregister(key);
+    }
+    const next = {};
+    for (const [key, setting] of Object.entries(loaded)) {
+      if (!same(key)) {
+        if (key in hosts || key in targets) {
+          console.log(`warn: ${file}: ${key} is already routed, skipped`);
+          continue;
+        }
+        register(key, setting, setting.url);
+      }
+      next[key] = setting;
+    }
+    current = next;
+  };
+  await sync();
+  watch(dirname(file), (_, filename) => {
+    if (filename !== basename(file)) return;
+    sync()
+      .then(() => emit("reload", { file, hosts: targets }))
+      .catch(console.error);
+  }).on("error", (err) => {
+    // e.g. the directory was removed; its hosts stay as they were
+    console.log(`warn: ${file}: no longer watched: ${err.message}`);
+  });
+}
+
+// keep compose-derived routes in sync with the compose file
+if (compose) {
+  await syncFile(resolve(compose.file), async () => {
+    const routes = await composeRoutes(compose);
+    const { key, cert } = compose;
+    return Object.fromEntries(
+      Object.entries(routes).map(([host, url]) => [host, { key, cert, url }]),
+    );
+  });
+}
+
+// and the hosts of every registered project with its sptth.yaml
+for (const dir of projects) {
+  await syncFile(resolve(dir, "sptth.yaml"), () => projectHosts(dir));
+}
+
+// startup summary: where sptth listens, each host with its upstream and
+// certificate, and warnings for the usual misconfigurations
+async function banner() {
+  const { name, version } = await readPackage();
+  const local = Object.values(networkInterfaces())
+    .flat()
+    .map(({ address }) => address);
+  const isLocal = (address) =>
+    address.startsWith("127.") || local.includes(address);
+  const listen = [
+    config.ssl && `https :${config.ssl.port}`,
+    config.port && `http :${config.port}`,
+  ];
+  const warnings = [];
+
+  console.log(`${name} ${version} ${yaml}`);
+  console.log(`listen ${listen.filter(Boolean).join(", ")}`);
+  for (const key of Object.keys({ ...hosts, ...settings })) {
+    const setting = settings[key] ?? hosts[key];
+    const url = targets[key] ?? "(waiting for command output)";
+    let cert = "no cert";
+    try {
+      const x509 = new X509Certificate(await readFile(setting.cert));
+      const validTo = new Date(x509.validTo);
+      cert = `cert until ${validTo.toISOString().slice(0, 10)}`;
+      if (x509.checkHost(key) === undefined) {
+        warnings.push(`${key}: ${setting.cert} is not valid for ${key}`);
+      }
+      if (validTo - Date.now() < 30 * 24 * 60 * 60 * 1000) {
+        warnings.push(`${key}: certificate expires ${validTo.toISOString()}`);
+      }
+    } catch (err) {
+      warnings.push(`${key}: cannot read ${setting.cert}: ${err.message}`);
+    }
+    const address = await lookup(key).then(
+      ({ address }) => address,
+      () => undefined,
+    );
+    if (address === undefined) {
+      warnings.push(`${key}: does not resolve, add it to /etc/hosts`);
+    } else if (!isLocal(address)) {
+      warnings.push(`${key}: resolves to ${address}, not this machine`);
+    }
+    const flag = setting.default ? " (default)" : "";
+    console.log(`  https://${key} => ${url}  ${cert}${flag}`);
+  }
+  if (localhost_ports) {
+    console.log(`  https://*.<port>.localhost => http://127.0.0.1:<port>`);
+  }
+  for (const warning of warnings) console.log(`warn: ${warning}`);
+}
+
+await banner();
+started = true;
+emit("start", { hosts: targets });
@@ -0,0 +1,24 @@
+// semver precedence: negative, zero or positive like a sort comparator;
+// a prerelease sorts before its release, build metadata is ignored
+export function compareVersions(a, b) {
+  const split = (version) => {
+    const [core, pre] = version.split("+")[0].split(/-(.*)/);
+    return [core.split(".").map(Number), pre?.split(".") ?? []];
+  };
+  const [coreA, preA] = split(a);
+  const [coreB, preB] = split(b);
+  for (let i = 0; i < 3; i++) {
+    if (coreA[i] !== coreB[i]) return coreA[i] - coreB[i];
+  }
+  if (preA.length === 0 || preB.length === 0) return preB.length - preA.length;
+  for (let i = 0; i < Math.max(preA.length, preB.length); i++) {
+    if (preA[i] === undefined) return -1;
+    if (preB[i] === undefined) return 1;
+    if (preA[i] === preB[i]) continue;
+    const [numA, numB] = [/^\d+$/.test(preA[i]), /^\d+$/.test(preB[i])];
+    if (numA && numB) return preA[i] - preB[i];
+    if (numA !== numB) return numA ? -1 : 1;
+    return preA[i] < preB[i] ? -1 : 1;
+  }
+  return 0;
+}
@@ -0,0 +1,34 @@
+import assert from "node:assert/strict";
+import { test } from "node:test";
+import { compareVersions } from "./version.js";
+
+test("compares the core numerically", () => {
+  assert.ok(compareVersions("1.10.0", "1.9.0") > 0);
+  assert.ok(compareVersions("0.0.1", "0.1.0") < 0);
+  assert.equal(compareVersions("1.2.3", "1.2.3"), 0);
+});
+
+test("sorts a prerelease before its release", () => {
+  assert.ok(compareVersions("1.0.0-rc.1", "1.0.0") < 0);
+  assert.ok(compareVersions("1.0.0", "1.0.0-rc.1") > 0);
+});
+
+test("orders prereleases by precedence", () => {
+  const ordered = [
+    "1.0.0-alpha",
+    "1.0.0-alpha.1",
+    "1.0.0-alpha.beta",
+    "1.0.0-beta",
+    "1.0.0-beta.2",
+    "1.0.0-beta.11",
+    "1.0.0-rc.1",
+    "1.0.0",
+  ];
+  const shuffled = [...ordered].reverse();
+  assert.deepEqual(shuffled.sort(compareVersions), ordered);
+});
+
+test("ignores build metadata", () => {
+  assert.equal(compareVersions("1.0.0+build.5", "1.0.0"), 0);
+  assert.ok(compareVersions("1.0.0-rc.1+sha.abc", "1.0.0") < 0);
+});